@@ -1,20 +1,216 @@
 use clap::Parser;
 use futures::StreamExt;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use serde::Serialize;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio_rustls::TlsConnector;
+
+/// 为 stdio、日志文件等保留的文件描述符数量，不计入扫描并发预算
+const FD_HEADROOM: u64 = 100;
+
+/// 无资源耗尽错误的窗口之后，并发上限每次增加的步长（加性增）
+const AIMD_STEP: usize = 64;
+
+/// 评估一次 AIMD 加性增所需的连续完成数
+const AIMD_WINDOW: usize = 256;
+
+/// nmap 常见端口列表的精简版，供 `--ports top100` 使用
+const TOP_100_PORTS: [u16; 100] = [
+    7, 9, 13, 21, 22, 23, 25, 26, 37, 53, 79, 80, 81, 88, 106, 110, 111, 113, 119, 135, 139, 143,
+    144, 179, 199, 254, 255, 280, 311, 389, 427, 443, 444, 445, 458, 464, 465, 497, 513, 514, 515,
+    543, 544, 548, 554, 587, 593, 625, 631, 636, 646, 787, 808, 873, 888, 902, 990, 993, 995,
+    1000, 1022, 1024, 1025, 1026, 1027, 1028, 1029, 1030, 1032, 1033, 1035, 1036, 1037, 1038,
+    1039, 1040, 1041, 1044, 1048, 1049, 1050, 1053, 1054, 1056, 1058, 1059, 1064, 1065, 1066,
+    1069, 1071, 1074, 1080, 1110, 1234, 1433, 1494, 1521, 1720, 1723,
+];
+
+/// `--ports` 解析失败时返回的错误
+#[derive(Debug, PartialEq, Eq)]
+enum ParseError {
+    Empty(String),
+    InvalidPort(String),
+    ReversedRange(u16, u16),
+    UnknownAlias(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty(segment) => write!(f, "empty port segment: {segment:?}"),
+            ParseError::InvalidPort(segment) => write!(f, "invalid port value: {segment:?}"),
+            ParseError::ReversedRange(start, end) => {
+                write!(f, "reversed port range: {start}-{end}")
+            }
+            ParseError::UnknownAlias(name) => write!(f, "unknown port alias: {name:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// 解析 `22,80,443,1-1024,8000-8100` 这样的端口规格，也支持
+/// `http`/`https`/`ssh`/`top100` 等常见别名，返回去重、排序后的端口列表
+fn parse_ports(spec: &str) -> Result<Vec<u16>, ParseError> {
+    let mut ports = Vec::new();
+
+    for segment in spec.split(',') {
+        let segment = segment.trim();
+        if segment.is_empty() {
+            return Err(ParseError::Empty(spec.to_string()));
+        }
+
+        match segment.to_ascii_lowercase().as_str() {
+            "http" => {
+                ports.push(80);
+                continue;
+            }
+            "https" => {
+                ports.push(443);
+                continue;
+            }
+            "ssh" => {
+                ports.push(22);
+                continue;
+            }
+            "top100" => {
+                ports.extend_from_slice(&TOP_100_PORTS);
+                continue;
+            }
+            _ => {}
+        }
+
+        if let Some((start, end)) = segment.split_once('-') {
+            let start: u16 = start
+                .trim()
+                .parse()
+                .map_err(|_| ParseError::InvalidPort(segment.to_string()))?;
+            let end: u16 = end
+                .trim()
+                .parse()
+                .map_err(|_| ParseError::InvalidPort(segment.to_string()))?;
+            if start > end {
+                return Err(ParseError::ReversedRange(start, end));
+            }
+            ports.extend(start..=end);
+        } else if let Ok(port) = segment.parse::<u16>() {
+            ports.push(port);
+        } else if segment.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(ParseError::UnknownAlias(segment.to_string()));
+        } else {
+            return Err(ParseError::InvalidPort(segment.to_string()));
+        }
+    }
+
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+/// 将 `-i`/`--target` 展开成具体的 (IP, 原始域名) 列表：支持逗号分隔的多个目标，
+/// 每个目标可以是字面 IP、域名（走 DNS 解析）或 `192.168.1.0/24` 这样的 CIDR 网段。
+/// 域名解析出的条目会保留原始域名，供 TLS SNI 和 HTTP `Host:` 头复用；
+/// 字面 IP 和 CIDR 展开出的地址没有域名可言
+async fn resolve_targets(spec: &str) -> Vec<(IpAddr, Option<String>)> {
+    let mut hosts = Vec::new();
+
+    for segment in spec.split(',') {
+        let segment = segment.trim();
+        if segment.is_empty() {
+            continue;
+        }
+
+        if let Some((network, prefix)) = segment.split_once('/') {
+            match expand_cidr(network, prefix) {
+                Ok(ips) => hosts.extend(ips.into_iter().map(|ip| (ip, None))),
+                Err(err) => eprintln!("skipping invalid CIDR {segment:?}: {err}"),
+            }
+            continue;
+        }
+
+        if let Ok(ip) = segment.parse::<IpAddr>() {
+            hosts.push((ip, None));
+            continue;
+        }
+
+        match tokio::net::lookup_host((segment, 0)).await {
+            Ok(addrs) => {
+                hosts.extend(addrs.map(|addr| (addr.ip(), Some(segment.to_string()))));
+            }
+            Err(err) => eprintln!("failed to resolve {segment:?}: {err}"),
+        }
+    }
+
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+/// 展开形如 `192.168.1.0/24` 的 IPv4 CIDR 网段为具体地址列表；
+/// 为避免意外展开超大网段，要求前缀长度至少为 /16
+fn expand_cidr(network: &str, prefix: &str) -> Result<Vec<IpAddr>, String> {
+    let base: Ipv4Addr = network
+        .parse()
+        .map_err(|_| format!("invalid network address {network:?}"))?;
+    let prefix: u32 = prefix
+        .parse()
+        .map_err(|_| format!("invalid prefix length {prefix:?}"))?;
+    if prefix > 32 {
+        return Err(format!("prefix length out of range: {prefix}"));
+    }
+    if prefix < 16 {
+        return Err(format!("refusing to expand a /{prefix} block, minimum is /16"));
+    }
+
+    let host_bits = 32 - prefix;
+    let mask: u32 = if host_bits >= 32 { 0 } else { !0u32 << host_bits };
+    let network_addr = u32::from(base) & mask;
+    let count: u32 = 1u32 << host_bits;
+
+    Ok((0..count)
+        .map(|offset| IpAddr::V4(Ipv4Addr::from(network_addr + offset)))
+        .collect())
+}
+
+/// `--output` 支持的输出格式
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[value(rename_all = "lowercase")]
+enum OutputFormat {
+    /// 人类可读的列表（默认）：边发现边输出，host 变化时先打印 host 标题行，
+    /// 因此同一 host 的端口可能分成好几段
+    Text,
+    /// 单个 JSON 数组，外加汇总信息；在扫描结束时一次性输出
+    Json,
+    /// 每发现一个开放端口就输出一行 JSON，便于管道消费
+    Jsonl,
+    /// 每发现一个开放端口就输出一行 CSV，表头在扫描开始时输出
+    Csv,
+}
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Config {
-    /// 目标 IP 地址
-    #[arg(short = 'i', long = "ip", default_value = "127.0.0.1")]
-    ip: String,
+    /// 扫描目标，支持逗号分隔的多个值：字面 IP、域名或 CIDR 网段（如 `192.168.1.0/24`）
+    #[arg(short = 'i', long = "target", default_value = "127.0.0.1")]
+    target: String,
 
-    /// 起始端口
+    /// 端口规格，例如 `22,80,443,1-1024,8000-8100` 或别名 `http`/`https`/`ssh`/`top100`；
+    /// 指定后会覆盖 `-s`/`-e`
+    #[arg(short = 'p', long = "ports")]
+    ports: Option<String>,
+
+    /// 起始端口（未指定 `-p` 时生效）
     #[arg(short = 's', long = "start", default_value_t = 1)]
     start_port: u16,
 
-    /// 结束端口
+    /// 结束端口（未指定 `-p` 时生效）
     #[arg(short = 'e', long = "end", default_value_t = 65535)]
     end_port: u16,
 
@@ -25,6 +221,509 @@ struct Config {
     /// 超时时间（毫秒）
     #[arg(short = 't', long = "timeout", default_value_t = 200)]
     timeout: u64,
+
+    /// 连接成功后尝试抓取服务 banner（SSH/FTP/SMTP 问候语、HTTP 响应头等）
+    #[arg(short = 'b', long = "banner", default_value_t = false)]
+    banner: bool,
+
+    /// 抓取 banner 的超时时间（毫秒）
+    #[arg(long = "banner-timeout", default_value_t = 500)]
+    banner_timeout: u64,
+
+    /// 抓取 banner 最多读取的字节数
+    #[arg(long = "banner-bytes", default_value_t = 256)]
+    banner_bytes: usize,
+
+    /// 对开放端口做一次 HTTP(S) 指纹识别：按端口号判断像不像 TLS，是则做 TLS 握手
+    /// 读取证书信息，否则发明文 HTTP/1.1 GET 读取状态行/Server/标题；握手失败时不再退回明文
+    #[arg(long = "http-probe", default_value_t = false)]
+    http_probe: bool,
+
+    /// 输出格式：text（默认）、json、jsonl 或 csv
+    #[arg(short = 'o', long = "output", value_enum, default_value = "text")]
+    output: OutputFormat,
+}
+
+/// 单个端口的扫描结果
+struct ScanResult {
+    host: IpAddr,
+    port: u16,
+    latency: Duration,
+    banner: Option<Vec<u8>>,
+    probe: Option<ProbeInfo>,
+}
+
+/// 供 `--output json`/`jsonl`/`csv` 序列化的扁平记录
+#[derive(Serialize)]
+struct PortRecord {
+    host: IpAddr,
+    port: u16,
+    state: &'static str,
+    banner: Option<String>,
+    latency_ms: u128,
+    probe: Option<String>,
+}
+
+impl From<&ScanResult> for PortRecord {
+    fn from(result: &ScanResult) -> Self {
+        PortRecord {
+            host: result.host,
+            port: result.port,
+            state: "open",
+            banner: result.banner.as_deref().map(format_banner),
+            latency_ms: result.latency.as_millis(),
+            probe: result.probe.as_ref().map(format_probe),
+        }
+    }
+}
+
+/// `--output json` 的汇总对象
+#[derive(Serialize)]
+struct Summary {
+    open_count: usize,
+    elapsed_ms: u128,
+}
+
+/// `--http-probe` 的指纹识别结果
+enum ProbeInfo {
+    Http {
+        status_line: String,
+        server: Option<String>,
+        title: Option<String>,
+    },
+    Tls {
+        alpn: Option<String>,
+        subject: String,
+        sans: Vec<String>,
+        not_after: String,
+    },
+}
+
+/// 单次连接尝试的结果：开放端口、确认关闭/过滤，或是本机资源耗尽
+enum ConnectOutcome {
+    Open(ScanResult),
+    Closed,
+    ResourceExhausted,
+}
+
+/// 查询进程的文件描述符软限制（通过 `rlimit`），并据此收紧有效并发宽度，
+/// 避免大规模扫描触发 "too many open files"
+fn fd_safe_concurrency(requested: usize) -> usize {
+    match rlimit::Resource::NOFILE.get() {
+        Ok((soft, _hard)) => {
+            let budget = soft.saturating_sub(FD_HEADROOM).max(1) as usize;
+            requested.min(budget)
+        }
+        Err(_) => requested,
+    }
+}
+
+/// 操作系统层面的资源耗尽（EMFILE/ENFILE），区别于单纯的连接被拒绝
+fn is_resource_exhausted(err: &std::io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EMFILE) | Some(libc::ENFILE))
+}
+
+async fn connect_one(
+    host: IpAddr,
+    hostname: Option<String>,
+    port: u16,
+    args: &Config,
+) -> ConnectOutcome {
+    let host_port = format!("{host}:{port}");
+    let attempt_started = Instant::now();
+    let mut stream = match tokio::time::timeout(
+        Duration::from_millis(args.timeout),
+        TcpStream::connect(host_port),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(err)) if is_resource_exhausted(&err) => return ConnectOutcome::ResourceExhausted,
+        Ok(Err(_)) | Err(_) => return ConnectOutcome::Closed,
+    };
+    let latency = attempt_started.elapsed();
+
+    let banner = if args.banner {
+        // 尽力而为地抓取 banner，读取失败或超时都不影响端口本身被判定为开放
+        let mut buf = vec![0u8; args.banner_bytes];
+        match tokio::time::timeout(
+            Duration::from_millis(args.banner_timeout),
+            stream.read(&mut buf),
+        )
+        .await
+        {
+            Ok(Ok(n)) if n > 0 => {
+                buf.truncate(n);
+                Some(buf)
+            }
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    let probe = if args.http_probe {
+        http_probe(host, hostname, port, stream, Duration::from_millis(args.timeout)).await
+    } else {
+        drop(stream);
+        None
+    };
+
+    ConnectOutcome::Open(ScanResult {
+        host,
+        port,
+        latency,
+        banner,
+        probe,
+    })
+}
+
+/// 接受任意证书的校验器：指纹识别只关心证书实际内容（包括过期/自签证书），
+/// 不关心证书链是否可信，因此故意跳过校验
+#[derive(Debug)]
+struct AcceptAnyCert;
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![
+            SignatureScheme::RSA_PKCS1_SHA1,
+            SignatureScheme::ECDSA_SHA1_Legacy,
+            SignatureScheme::RSA_PKCS1_SHA256,
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA384,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::RSA_PKCS1_SHA512,
+            SignatureScheme::ECDSA_NISTP521_SHA512,
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PSS_SHA384,
+            SignatureScheme::RSA_PSS_SHA512,
+            SignatureScheme::ED25519,
+        ]
+    }
+}
+
+/// 对一个开放端口做 HTTP(S) 指纹识别，复用 `connect_one` 已经建立的那条连接
+/// （而不是像 reqwest/hyper 客户端那样再拨一次号）：按端口号判断像不像 TLS，
+/// 命中就走 TLS 握手并报告 ALPN 协议与证书 subject/SAN/过期时间，否则退回
+/// 明文 HTTP/1.1 GET，报告状态行、`Server` 响应头与页面 `<title>`。
+/// `hostname` 是目标原本的域名（而非解析出的 IP），基于名字的虚拟主机需要
+/// 它来发出正确的 SNI/`Host:`，否则拿到的会是默认站点的证书和页面
+async fn http_probe(
+    host: IpAddr,
+    hostname: Option<String>,
+    port: u16,
+    stream: TcpStream,
+    timeout: Duration,
+) -> Option<ProbeInfo> {
+    if looks_like_tls(port) {
+        tokio::time::timeout(timeout, probe_tls(host, hostname, stream))
+            .await
+            .ok()
+            .flatten()
+    } else {
+        tokio::time::timeout(timeout, probe_http(host, hostname, stream))
+            .await
+            .ok()
+            .flatten()
+    }
+}
+
+/// 常见的 TLS 端口；命中时优先尝试 TLS 握手，而不是两边都试一遍
+/// （一条连接上没法先握手失败再退回明文重试，tokio-rustls 失败时不归还 stream）
+const TLS_PORT_HINTS: &[u16] = &[443, 465, 636, 989, 990, 993, 995, 3269, 5061, 8443, 9443];
+
+fn looks_like_tls(port: u16) -> bool {
+    TLS_PORT_HINTS.contains(&port)
+}
+
+async fn probe_tls(host: IpAddr, hostname: Option<String>, stream: TcpStream) -> Option<ProbeInfo> {
+    let mut config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCert))
+        .with_no_client_auth();
+    // 不主动提供 ALPN 协议的话服务端没有什么可协商的，session.alpn_protocol()
+    // 就永远是 None——这里的列表只是为了让协商有东西可谈，不代表我们会用 h2
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let connector = TlsConnector::from(Arc::new(config));
+
+    let server_name = match hostname {
+        Some(name) => ServerName::try_from(name).unwrap_or_else(|_| ServerName::from(host)),
+        None => ServerName::from(host),
+    };
+    let tls_stream = connector.connect(server_name, stream).await.ok()?;
+    let (_, session) = tls_stream.get_ref();
+
+    let alpn = session
+        .alpn_protocol()
+        .map(|p| String::from_utf8_lossy(p).into_owned());
+    let cert = session.peer_certificates()?.first()?.clone();
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+
+    Some(ProbeInfo::Tls {
+        alpn,
+        subject: parsed.subject().to_string(),
+        sans: extract_sans(&parsed),
+        not_after: parsed.validity().not_after.to_string(),
+    })
+}
+
+/// 取出证书 SAN 扩展里的 DNS 名和 IP 地址条目
+fn extract_sans(cert: &x509_parser::certificate::X509Certificate) -> Vec<String> {
+    cert.subject_alternative_name()
+        .ok()
+        .flatten()
+        .map(|ext| {
+            ext.value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    x509_parser::extensions::GeneralName::DNSName(dns) => Some(dns.to_string()),
+                    x509_parser::extensions::GeneralName::IPAddress(bytes) => {
+                        san_ip_to_string(bytes)
+                    }
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// SAN 里的 IP 地址条目是原始字节，4 字节是 IPv4，16 字节是 IPv6
+fn san_ip_to_string(bytes: &[u8]) -> Option<String> {
+    match bytes.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?).to_string()),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?).to_string()),
+        _ => None,
+    }
+}
+
+async fn probe_http(host: IpAddr, hostname: Option<String>, mut stream: TcpStream) -> Option<ProbeInfo> {
+    let host_header = hostname.unwrap_or_else(|| host.to_string());
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: {host_header}\r\nConnection: close\r\nUser-Agent: zig-http/1.0\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await.ok()?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.ok()?;
+    let response = String::from_utf8_lossy(&response);
+
+    let mut lines = response.split("\r\n");
+    let status_line = lines.next()?.to_string();
+
+    let mut server = None;
+    let mut body_lines = Vec::new();
+    let mut in_headers = true;
+    for line in lines {
+        if in_headers {
+            if line.is_empty() {
+                in_headers = false;
+                continue;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("server") {
+                    server = Some(value.trim().to_string());
+                }
+            }
+        } else {
+            body_lines.push(line);
+        }
+    }
+
+    let title = extract_title(&body_lines.join("\n"));
+    Some(ProbeInfo::Http {
+        status_line,
+        server,
+        title,
+    })
+}
+
+/// 从 HTML 正文中粗略提取 `<title>` 标签内容
+fn extract_title(body: &str) -> Option<String> {
+    let lower = body.to_ascii_lowercase();
+    let start = lower.find("<title>")? + "<title>".len();
+    let end = start + lower[start..].find("</title>")?;
+    Some(body[start..end].trim().to_string())
+}
+
+/// 把 `ProbeInfo` 渲染成一行可读的诊断信息
+fn format_probe(probe: &ProbeInfo) -> String {
+    match probe {
+        ProbeInfo::Http {
+            status_line,
+            server,
+            title,
+        } => {
+            let server = server.as_deref().unwrap_or("-");
+            let title = title.as_deref().unwrap_or("-");
+            format!("HTTP: {status_line} | Server: {server} | Title: {title}")
+        }
+        ProbeInfo::Tls {
+            alpn,
+            subject,
+            sans,
+            not_after,
+        } => {
+            let alpn = alpn.as_deref().unwrap_or("-");
+            let sans = if sans.is_empty() {
+                "-".to_string()
+            } else {
+                sans.join(",")
+            };
+            format!("TLS: alpn={alpn} subject=\"{subject}\" san=[{sans}] expires={not_after}")
+        }
+    }
+}
+
+/// 将抓取到的 banner 转成可打印的字符串，控制字符用转义序列表示
+fn format_banner(raw: &[u8]) -> String {
+    String::from_utf8_lossy(raw)
+        .chars()
+        .map(|c| match c {
+            '\n' => "\\n".to_string(),
+            '\r' => "\\r".to_string(),
+            '\t' => "\\t".to_string(),
+            c if c.is_control() => format!("\\x{:02x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// 按 `--output` 选择的格式逐步输出结果；`json` 需要等扫描结束后一次性输出
+/// 数组，其余格式边扫描边输出，便于管道消费
+struct OutputSink {
+    format: OutputFormat,
+    current_host: Option<IpAddr>,
+    csv_writer: Option<csv::Writer<std::io::Stdout>>,
+    json_buffer: Vec<PortRecord>,
+}
+
+/// CSV 表头，字段顺序与 `PortRecord` 保持一致
+const CSV_HEADER: &[&str] = &["host", "port", "state", "banner", "latency_ms", "probe"];
+
+impl OutputSink {
+    fn new(format: OutputFormat) -> Self {
+        if format == OutputFormat::Text {
+            println!("Open ports:");
+        }
+        let csv_writer = (format == OutputFormat::Csv).then(|| {
+            // 自己管理表头行，这样扫描结果为零也能在扫描开始时就写出表头
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(std::io::stdout());
+            writer
+                .write_record(CSV_HEADER)
+                .expect("failed to write csv header");
+            writer.flush().expect("failed to flush csv writer");
+            writer
+        });
+        OutputSink {
+            format,
+            current_host: None,
+            csv_writer,
+            json_buffer: Vec::new(),
+        }
+    }
+
+    fn record_open(&mut self, result: &ScanResult) {
+        match self.format {
+            OutputFormat::Text => {
+                if self.current_host != Some(result.host) {
+                    println!("{}:", result.host);
+                    self.current_host = Some(result.host);
+                }
+                match &result.banner {
+                    Some(banner) => println!("  {} - {}", result.port, format_banner(banner)),
+                    None => println!("  {}", result.port),
+                }
+                if let Some(probe) = &result.probe {
+                    println!("      {}", format_probe(probe));
+                }
+            }
+            OutputFormat::Jsonl => {
+                let record = PortRecord::from(result);
+                println!(
+                    "{}",
+                    serde_json::to_string(&record).expect("PortRecord is always serializable")
+                );
+            }
+            OutputFormat::Csv => {
+                let writer = self
+                    .csv_writer
+                    .as_mut()
+                    .expect("csv writer is initialized for OutputFormat::Csv");
+                writer
+                    .serialize(PortRecord::from(result))
+                    .expect("failed to write csv row");
+                writer.flush().expect("failed to flush csv writer");
+            }
+            OutputFormat::Json => {
+                self.json_buffer.push(PortRecord::from(result));
+            }
+        }
+    }
+
+    fn finish(self, open_count: usize, elapsed: Duration, effective_rate: f64, concurrency: usize) {
+        match self.format {
+            OutputFormat::Text => {
+                println!(
+                    "Execution Time: {elapsed:?} ({open_count} open ports found, effective rate: {effective_rate:.0} conn/s, final concurrency: {concurrency})"
+                );
+            }
+            OutputFormat::Jsonl => {
+                let summary = Summary {
+                    open_count,
+                    elapsed_ms: elapsed.as_millis(),
+                };
+                println!(
+                    "{}",
+                    serde_json::to_string(&summary).expect("Summary is always serializable")
+                );
+            }
+            OutputFormat::Csv => {
+                // 结果已逐行输出，这里不需要再写汇总行
+            }
+            OutputFormat::Json => {
+                let payload = serde_json::json!({
+                    "results": self.json_buffer,
+                    "summary": Summary { open_count, elapsed_ms: elapsed.as_millis() },
+                });
+                println!(
+                    "{}",
+                    serde_json::to_string(&payload).expect("payload is always serializable")
+                );
+            }
+        }
+    }
 }
 
 #[tokio::main]
@@ -32,33 +731,170 @@ async fn main() {
     let args = Config::parse();
     let start = Instant::now();
 
-    // 使用缓冲流控制并发
-    let mut result: Vec<_> = futures::stream::iter(args.start_port..=args.end_port)
-        .map(|port| {
-            let host_port = format!("{}:{}", args.ip, port);
+    let ports = match &args.ports {
+        Some(spec) => match parse_ports(spec) {
+            Ok(ports) => ports,
+            Err(err) => {
+                eprintln!("invalid --ports value: {err}");
+                std::process::exit(1);
+            }
+        },
+        None => (args.start_port..=args.end_port).collect(),
+    };
+
+    let hosts = resolve_targets(&args.target).await;
+    if hosts.is_empty() {
+        eprintln!("no valid targets to scan");
+        std::process::exit(1);
+    }
+
+    // 并发预算覆盖整个 host × port 笛卡尔积，而不是按 host 单独计算；
+    // 域名解析出的条目把原始域名也带进笛卡尔积，探测阶段才能用它做 SNI/Host
+    let pending: Vec<(IpAddr, Option<String>, u16)> = hosts
+        .iter()
+        .flat_map(|(host, hostname)| {
+            let host = *host;
+            let hostname = hostname.clone();
+            ports.iter().map(move |&port| (host, hostname.clone(), port))
+        })
+        .collect();
+    let total = pending.len();
+
+    // 先用 ulimit 收紧一次上限，再让 AIMD 围绕这个上限自适应调整实际并发宽度；
+    // Semaphore 的持有许可数才是真正的并发闸门，ceiling 只是 buffer_unordered
+    // 的上界，避免一次性把所有待扫描目标都塞进内存里 poll
+    let ceiling = fd_safe_concurrency(args.concurrency);
+    let mut limit = ceiling;
+    let semaphore = Arc::new(Semaphore::new(limit));
+
+    let mut open_count = 0usize;
+    let mut sink = OutputSink::new(args.output);
+
+    let mut attempts = futures::stream::iter(pending)
+        .map(|(host, hostname, port)| {
+            let args = args.clone();
+            let semaphore = semaphore.clone();
             async move {
-                if let Ok(Ok(_)) = tokio::time::timeout(
-                    Duration::from_millis(args.timeout),
-                    TcpStream::connect(host_port),
-                )
-                .await
-                {
-                    Some(port)
-                } else {
-                    None
-                }
+                let permit = semaphore
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore never closes");
+                let outcome = connect_one(host, hostname, port, &args).await;
+                drop(permit);
+                outcome
             }
         })
-        .buffer_unordered(args.concurrency) // 最大并发数
-        .filter_map(|port| async move { port })
-        .collect()
-        .await;
+        .buffer_unordered(ceiling);
+
+    // 每凑够一个窗口的结果就评估一次，而不是等一整批全部跑完再调整：
+    // 后者会把吞吐量钉在本批最慢成员身上（关闭的端口大多会等到超时）
+    let mut window_resource_errors = 0usize;
+    let mut window_size = 0usize;
+    // 腰斩时仍被在途连接占用、没能立即收回的许可数，等它们归还后再陆续收回
+    let mut owed_permits = 0usize;
+
+    while let Some(outcome) = attempts.next().await {
+        match outcome {
+            ConnectOutcome::Open(result) => {
+                // 一发现开放端口就输出，不等整个窗口跑完
+                sink.record_open(&result);
+                open_count += 1;
+            }
+            ConnectOutcome::ResourceExhausted => window_resource_errors += 1,
+            ConnectOutcome::Closed => {}
+        }
+        window_size += 1;
+
+        if owed_permits > 0 {
+            owed_permits -= semaphore.forget_permits(owed_permits);
+        }
+
+        // 乘性减：只要出现资源耗尽就立即腰斩，不等凑满一个窗口
+        if window_resource_errors > 0 {
+            let new_limit = (limit / 2).max(1);
+            let wanted = limit - new_limit;
+            owed_permits += wanted - semaphore.forget_permits(wanted);
+            limit = new_limit;
+            window_resource_errors = 0;
+            window_size = 0;
+        } else if window_size >= AIMD_WINDOW {
+            // 加性增：整个窗口都没有资源耗尽时，小步扩大并发宽度；
+            // 关闭/拒绝连接是扫描中的常态，不能作为"还能更快"的信号
+            if limit < ceiling {
+                let new_limit = (limit + AIMD_STEP).min(ceiling);
+                let grow = new_limit - limit;
+                let cancelled = grow.min(owed_permits);
+                owed_permits -= cancelled;
+                semaphore.add_permits(grow - cancelled);
+                limit = new_limit;
+            }
+            window_size = 0;
+        }
+    }
 
-    result.sort();
+    let elapsed = start.elapsed();
+    let effective_rate = total as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    sink.finish(open_count, elapsed, effective_rate, limit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_ports_and_aliases() {
+        assert_eq!(parse_ports("80").unwrap(), vec![80]);
+        assert_eq!(parse_ports("http").unwrap(), vec![80]);
+        assert_eq!(parse_ports("https").unwrap(), vec![443]);
+        assert_eq!(parse_ports("ssh").unwrap(), vec![22]);
+    }
+
+    #[test]
+    fn parses_lists_and_ranges() {
+        assert_eq!(parse_ports("22,80,443").unwrap(), vec![22, 80, 443]);
+        assert_eq!(parse_ports("1-5").unwrap(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn dedups_overlapping_ranges_and_sorts() {
+        assert_eq!(
+            parse_ports("80,1-5,3-7,22").unwrap(),
+            vec![1, 2, 3, 4, 5, 6, 7, 22, 80]
+        );
+    }
 
-    let count = result.len();
-    print!("Open ports ({count} found):\n");
+    #[test]
+    fn rejects_reversed_range() {
+        assert_eq!(parse_ports("100-10"), Err(ParseError::ReversedRange(100, 10)));
+    }
 
-    result.into_iter().for_each(|port| print!("  {port}\n"));
-    println!("Execution Time: {:?}", start.elapsed());
-}
\ No newline at end of file
+    #[test]
+    fn rejects_empty_segment() {
+        assert_eq!(
+            parse_ports("22,,80"),
+            Err(ParseError::Empty("22,,80".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_u16_range_value() {
+        assert_eq!(
+            parse_ports("70000"),
+            Err(ParseError::InvalidPort("70000".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_alias() {
+        assert_eq!(
+            parse_ports("ftp"),
+            Err(ParseError::UnknownAlias("ftp".to_string()))
+        );
+    }
+
+    #[test]
+    fn top100_alias_expands_to_a_hundred_unique_ports() {
+        let ports = parse_ports("top100").unwrap();
+        assert_eq!(ports.len(), 100);
+    }
+}